@@ -2,10 +2,14 @@
 
 use std::fmt;
 use std::io::{self, Read, Write, Error, ErrorKind};
+use std::thread;
 use async::async_stdin;
 use std::time::{SystemTime, Duration};
 use raw::CONTROL_SEQUENCE_TIMEOUT;
-use sys::tty;
+
+pub mod parse;
+#[cfg(windows)]
+pub mod windows;
 
 derive_csi_sequence!("Hide the cursor.", Hide, "?25l");
 derive_csi_sequence!("Show the cursor.", Show, "?25h");
@@ -13,6 +17,44 @@ derive_csi_sequence!("Show the cursor.", Show, "?25h");
 derive_csi_sequence!("Restore the cursor.", Restore, "u");
 derive_csi_sequence!("Save the cursor.", Save, "s");
 
+/// Change the cursor style, as set by DECSCUSR.
+///
+/// Editors and other TUIs can use this to distinguish, e.g., insert mode
+/// from normal mode by swapping between a bar and a block cursor.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SetCursorStyle {
+    /// Reset the cursor to the user's default shape.
+    DefaultUserShape,
+    /// Blinking block.
+    BlinkingBlock,
+    /// Steady block.
+    SteadyBlock,
+    /// Blinking underline.
+    BlinkingUnderline,
+    /// Steady underline.
+    SteadyUnderline,
+    /// Blinking bar.
+    BlinkingBar,
+    /// Steady bar.
+    SteadyBar,
+}
+
+impl fmt::Display for SetCursorStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shape = match *self {
+            SetCursorStyle::DefaultUserShape => 0,
+            SetCursorStyle::BlinkingBlock => 1,
+            SetCursorStyle::SteadyBlock => 2,
+            SetCursorStyle::BlinkingUnderline => 3,
+            SetCursorStyle::SteadyUnderline => 4,
+            SetCursorStyle::BlinkingBar => 5,
+            SetCursorStyle::SteadyBar => 6,
+        };
+
+        write!(f, csi!("{} q"), shape)
+    }
+}
+
 /// Goto some position ((1,1)-based).
 ///
 /// # Why one-based?
@@ -93,55 +135,42 @@ pub trait DetectCursorPos {
     fn cursor_pos(&mut self) -> io::Result<(u16, u16)>;
 }
 
-pub enum AnsiState {
-    Norm,
-    Esc,
-    Csi,
-    Osc,
-}
-
+#[cfg(not(windows))]
 impl<W: Write> DetectCursorPos for W {
     fn cursor_pos(&mut self) -> io::Result<(u16, u16)> {
-        let mut stdin = tty::get_tty()?;
+        // `async_stdin` already reads via the tty rather than the
+        // process's stdin, so this keeps working even when stdin itself
+        // is redirected to a pipe (which is what the `size::terminal_size`
+        // fallback relies on).
+        let mut stdin = async_stdin();
 
         write!(self, "\x1B[6n")?;
         self.flush()?;
 
-        let mut arg = String::new();
-        let mut s = AnsiState::Norm;
-        for b_res in stdin.bytes() {
-            let b = b_res?;
-            match s {
-                AnsiState::Norm => match b {
-                    b'\x1B' => s = AnsiState::Esc,
-                    _ => (),
-                },
-                AnsiState::Esc => match b {
-                    b'[' => {
-                        arg.clear();
-                        s = AnsiState::Csi;
-                    },
-                    b']' => s = AnsiState::Osc,
-                    _ => s = AnsiState::Norm,
-                },
-                AnsiState::Csi => match b {
-                    b'R' => {
-                        let mut parts = arg.split(';');
-                        let y = parts.next().unwrap_or("").parse::<u16>().unwrap_or(0);
-                        let x = parts.next().unwrap_or("").parse::<u16>().unwrap_or(0);
-                        return Ok((x, y));
-                    },
-                    b'A' ... b'Z' | b'a' ... b'z' => s = AnsiState::Norm,
-                    b'0' ... b'9' | b';' => arg.push(b as char),
-                    _ => ()
-                },
-                AnsiState::Osc => match b {
-                    b'\x07' => s = AnsiState::Norm,
-                    _ => (),
+        let timeout = Duration::from_millis(CONTROL_SEQUENCE_TIMEOUT);
+        let now = SystemTime::now();
+
+        let mut parser = parse::Parser::new();
+        let mut buf = [0; 1];
+        loop {
+            if stdin.read(&mut buf)? == 0 {
+                if now.elapsed().unwrap_or(timeout) >= timeout {
+                    return Err(Error::new(ErrorKind::Other,
+                                          "Timeout while waiting for cursor position report"));
                 }
+
+                thread::sleep(Duration::from_millis(1));
+                continue;
             }
-        }
 
-        Err(Error::new(ErrorKind::Other, "Cursor position not found"))
+            if let Some(parse::Event::CursorPosition(x, y)) = parser.advance(buf[0]) {
+                return Ok((x, y));
+            }
+
+            if now.elapsed().unwrap_or(timeout) >= timeout {
+                return Err(Error::new(ErrorKind::Other,
+                                      "Timeout while waiting for cursor position report"));
+            }
+        }
     }
 }
@@ -0,0 +1,265 @@
+//! Incremental parsing of the control sequences a terminal sends back in
+//! response to a query (cursor position, device status, window title, ...).
+//!
+//! `cursor_pos` only ever needed to recognize a single reply, so the parsing
+//! used to be a small hand-rolled state machine baked into that function.
+//! As termion grows more query/response features, that logic is factored
+//! out here so it can be shared and extended.
+
+use std::mem;
+
+/// A fully parsed terminal response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A cursor position report, `CSI row ; col R`.
+    CursorPosition(u16, u16),
+    /// A device status report, `CSI 0 n`.
+    DeviceStatus,
+    /// A device attributes report, `CSI ... c`.
+    DeviceAttributes,
+    /// An OSC string, terminated by either BEL or ST (`ESC \`).
+    OscString(String),
+    /// A recognized escape sequence that this parser doesn't (yet) know how
+    /// to interpret. Carries the raw bytes of the sequence, including the
+    /// leading `ESC`, so nothing is silently dropped.
+    Unknown(Vec<u8>),
+}
+
+enum State {
+    /// Not currently inside a sequence.
+    Ground,
+    /// Just saw `ESC`.
+    Escape,
+    /// Inside a CSI (`ESC [ ...`) sequence, accumulating parameter bytes
+    /// (`0x30...0x3F`) and intermediate bytes (`0x20...0x2F`) per the
+    /// ECMA-48 grammar, waiting for a final byte (`0x40...0x7E`).
+    Csi { params: String, intermediates: Vec<u8> },
+    /// Inside an OSC (`ESC ] ...`) string, waiting for either a bare `BEL`
+    /// or an `ESC \` (ST) terminator. The body is kept as raw bytes and
+    /// only decoded as UTF-8 once the terminator is seen, since a
+    /// multi-byte character can straddle several `advance` calls.
+    Osc { body: Vec<u8>, seen_esc: bool },
+}
+
+/// An incremental parser for terminal query responses.
+///
+/// Bytes are fed one at a time with [`advance`](#method.advance). Whenever a
+/// full sequence has been recognized, the corresponding [`Event`] is
+/// returned; otherwise `advance` returns `None` and the parser keeps its
+/// place for the next byte.
+pub struct Parser {
+    state: State,
+    raw: Vec<u8>,
+}
+
+impl Parser {
+    /// Create a new parser, ready to receive bytes from the start of a
+    /// sequence.
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            raw: Vec::new(),
+        }
+    }
+
+    /// The raw bytes of the sequence `advance` just completed (valid right
+    /// after a call to `advance` that returned `Some`), including the
+    /// leading `ESC`.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Whether the parser is at the start of a sequence, i.e. the next byte
+    /// fed to it will either be plain data or the start of a new sequence,
+    /// rather than a continuation of one already in progress.
+    pub fn is_idle(&self) -> bool {
+        match self.state {
+            State::Ground => true,
+            _ => false,
+        }
+    }
+
+    /// Feed a single byte to the parser, returning an event if this byte
+    /// completed one.
+    pub fn advance(&mut self, b: u8) -> Option<Event> {
+        match mem::replace(&mut self.state, State::Ground) {
+            State::Ground => {
+                if b == b'\x1B' {
+                    self.raw.clear();
+                    self.raw.push(b);
+                    self.state = State::Escape;
+                }
+                None
+            },
+            State::Escape => {
+                self.raw.push(b);
+                match b {
+                    b'[' => {
+                        self.state = State::Csi { params: String::new(), intermediates: Vec::new() };
+                        None
+                    },
+                    b']' => {
+                        self.state = State::Osc { body: Vec::new(), seen_esc: false };
+                        None
+                    },
+                    _ => Some(Event::Unknown(self.raw.clone())),
+                }
+            },
+            State::Csi { mut params, mut intermediates } => {
+                if b == b'\x1B' {
+                    // The terminal abandoned this sequence and started a
+                    // new one; recover instead of waiting forever for a
+                    // final byte that will never come.
+                    self.abandon();
+                    return self.advance(b);
+                }
+
+                self.raw.push(b);
+                match b {
+                    0x30...0x3F => {
+                        params.push(b as char);
+                        self.state = State::Csi { params: params, intermediates: intermediates };
+                        None
+                    },
+                    0x20...0x2F => {
+                        intermediates.push(b);
+                        self.state = State::Csi { params: params, intermediates: intermediates };
+                        None
+                    },
+                    0x40...0x7E => Some(Self::finish_csi(b, &params, &intermediates, &self.raw)),
+                    _ => Some(Event::Unknown(self.raw.clone())),
+                }
+            },
+            State::Osc { mut body, seen_esc } => {
+                if seen_esc {
+                    self.raw.push(b);
+                    if b == b'\\' {
+                        return Some(Event::OscString(String::from_utf8_lossy(&body).into_owned()));
+                    }
+
+                    // That wasn't a `ST`; the terminal has moved on to a
+                    // new sequence, which began with that `ESC`.
+                    self.abandon();
+                    self.advance(b'\x1B');
+                    return self.advance(b);
+                }
+
+                self.raw.push(b);
+                match b {
+                    b'\x07' => Some(Event::OscString(String::from_utf8_lossy(&body).into_owned())),
+                    b'\x1B' => {
+                        self.state = State::Osc { body: body, seen_esc: true };
+                        None
+                    },
+                    _ => {
+                        body.push(b);
+                        self.state = State::Osc { body: body, seen_esc: false };
+                        None
+                    },
+                }
+            },
+        }
+    }
+
+    /// Drop whatever sequence is in progress and return to the ground
+    /// state.
+    fn abandon(&mut self) {
+        self.state = State::Ground;
+        self.raw.clear();
+    }
+
+    fn finish_csi(final_byte: u8, params: &str, intermediates: &[u8], raw: &[u8]) -> Event {
+        if intermediates.is_empty() && final_byte == b'R' {
+            let mut parts = params.split(';');
+            let y = parts.next().unwrap_or("").parse::<u16>().unwrap_or(0);
+            let x = parts.next().unwrap_or("").parse::<u16>().unwrap_or(0);
+            Event::CursorPosition(x, y)
+        } else if intermediates.is_empty() && final_byte == b'n' {
+            Event::DeviceStatus
+        } else if final_byte == b'c' {
+            Event::DeviceAttributes
+        } else {
+            Event::Unknown(raw.to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Parser};
+
+    /// Feed every byte of `input` to a fresh parser, returning the events it
+    /// produced, in order.
+    fn events(input: &[u8]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        input.iter().filter_map(|&b| parser.advance(b)).collect()
+    }
+
+    #[test]
+    fn cursor_position_report() {
+        assert_eq!(events(b"\x1B[24;80R"), vec![Event::CursorPosition(80, 24)]);
+    }
+
+    #[test]
+    fn device_status_report() {
+        assert_eq!(events(b"\x1B[0n"), vec![Event::DeviceStatus]);
+    }
+
+    #[test]
+    fn device_attributes_report() {
+        assert_eq!(events(b"\x1B[?1;2c"), vec![Event::DeviceAttributes]);
+    }
+
+    #[test]
+    fn osc_string_terminated_by_bel() {
+        assert_eq!(events(b"\x1B]0;title\x07"),
+                   vec![Event::OscString("0;title".to_owned())]);
+    }
+
+    #[test]
+    fn osc_string_terminated_by_st() {
+        assert_eq!(events(b"\x1B]0;title\x1B\\"),
+                   vec![Event::OscString("0;title".to_owned())]);
+    }
+
+    #[test]
+    fn osc_string_decodes_utf8() {
+        assert_eq!(events("\x1B]0;caf\u{e9}\x07".as_bytes()),
+                   vec![Event::OscString("0;caf\u{e9}".to_owned())]);
+    }
+
+    #[test]
+    fn unknown_csi_sequence_carries_raw_bytes() {
+        assert_eq!(events(b"\x1B[1;31m"), vec![Event::Unknown(b"\x1B[1;31m".to_vec())]);
+    }
+
+    #[test]
+    fn bare_esc_not_followed_by_bracket_is_unknown() {
+        assert_eq!(events(b"\x1Bc"), vec![Event::Unknown(b"\x1Bc".to_vec())]);
+    }
+
+    #[test]
+    fn new_esc_recovers_from_unterminated_csi() {
+        // An abandoned CSI sequence (no final byte ever arrives) must not
+        // swallow the ESC that starts the next, real sequence.
+        assert_eq!(events(b"\x1B[5\x1B[3;4R"), vec![Event::CursorPosition(4, 3)]);
+    }
+
+    #[test]
+    fn new_esc_recovers_from_unterminated_osc() {
+        assert_eq!(events(b"\x1B]0;oops\x1Bx\x1B[3;4R"),
+                   vec![Event::Unknown(b"\x1Bx".to_vec()), Event::CursorPosition(4, 3)]);
+    }
+
+    #[test]
+    fn is_idle_tracks_sequence_progress() {
+        let mut parser = Parser::new();
+        assert!(parser.is_idle());
+        parser.advance(b'\x1B');
+        assert!(!parser.is_idle());
+        parser.advance(b'[');
+        assert!(!parser.is_idle());
+        parser.advance(b'R');
+        assert!(parser.is_idle());
+    }
+}
@@ -0,0 +1,243 @@
+//! A Windows Console API backend for cursor control.
+//!
+//! The ANSI escapes the rest of this module emits only move the cursor on
+//! terminals that actually interpret VT sequences. On Windows that means a
+//! recent console with `ENABLE_VIRTUAL_TERMINAL_PROCESSING` turned on;
+//! older `cmd.exe`/`conhost.exe` consoles print them as literal text
+//! instead of moving anything.
+//!
+//! [`WindowsConsole`](struct.WindowsConsole.html) wraps a writer backed by
+//! such a console. Rather than forwarding bytes verbatim, it feeds them
+//! through a [`cursor::parse::Parser`](../parse/struct.Parser.html) and
+//! turns the cursor-control sequences it recognizes into direct Win32
+//! Console API calls, so the very same `Goto`/`Up`/`Hide`/... types used
+//! elsewhere in termion work unmodified on these consoles too.
+
+use std::io::{self, Write};
+use std::mem;
+use std::os::windows::io::AsRawHandle;
+
+use cursor::DetectCursorPos;
+use cursor::parse::{self, Event};
+
+type Handle = ::std::os::windows::raw::HANDLE;
+type Bool = i32;
+type Word = u16;
+type Dword = u32;
+type Short = i16;
+
+#[repr(C)]
+struct Coord {
+    x: Short,
+    y: Short,
+}
+
+#[repr(C)]
+struct SmallRect {
+    left: Short,
+    top: Short,
+    right: Short,
+    bottom: Short,
+}
+
+#[repr(C)]
+struct ConsoleScreenBufferInfo {
+    size: Coord,
+    cursor_position: Coord,
+    attributes: Word,
+    window: SmallRect,
+    maximum_window_size: Coord,
+}
+
+#[repr(C)]
+struct ConsoleCursorInfo {
+    size: Dword,
+    visible: Bool,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetConsoleScreenBufferInfo(handle: Handle, info: *mut ConsoleScreenBufferInfo) -> Bool;
+    fn SetConsoleCursorPosition(handle: Handle, position: Coord) -> Bool;
+    fn GetConsoleCursorInfo(handle: Handle, info: *mut ConsoleCursorInfo) -> Bool;
+    fn SetConsoleCursorInfo(handle: Handle, info: *const ConsoleCursorInfo) -> Bool;
+}
+
+fn screen_buffer_info(handle: Handle) -> io::Result<ConsoleScreenBufferInfo> {
+    unsafe {
+        let mut info: ConsoleScreenBufferInfo = mem::zeroed();
+        if GetConsoleScreenBufferInfo(handle, &mut info) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(info)
+    }
+}
+
+fn set_cursor_visible(handle: Handle, visible: bool) -> io::Result<()> {
+    unsafe {
+        let mut info: ConsoleCursorInfo = mem::zeroed();
+        if GetConsoleCursorInfo(handle, &mut info) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        info.visible = visible as Bool;
+        if SetConsoleCursorInfo(handle, &info) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Wraps a writer backed by a Windows console handle, translating the
+/// cursor-control sequences termion writes into Win32 Console API calls
+/// instead of forwarding raw ANSI bytes.
+pub struct WindowsConsole<W> {
+    inner: W,
+    parser: parse::Parser,
+    saved: Option<Coord>,
+}
+
+impl<W: Write + AsRawHandle> WindowsConsole<W> {
+    /// Wrap `inner`, which must be backed by a console handle.
+    pub fn new(inner: W) -> WindowsConsole<W> {
+        WindowsConsole {
+            inner: inner,
+            parser: parse::Parser::new(),
+            saved: None,
+        }
+    }
+
+    fn handle(&self) -> Handle {
+        self.inner.as_raw_handle()
+    }
+
+    /// Try to translate `raw` (a full escape sequence, leading `ESC`
+    /// included) into Console API calls. Returns whether it was one of the
+    /// sequences this backend understands; an unrecognized sequence (e.g.
+    /// an SGR color code) is left for the caller to forward unchanged.
+    fn apply(&mut self, raw: &[u8]) -> io::Result<bool> {
+        if raw.len() < 3 || raw[0] != b'\x1B' || raw[1] != b'[' {
+            return Ok(false);
+        }
+
+        let final_byte = raw[raw.len() - 1];
+        let params: String = raw[2..raw.len() - 1].iter().map(|&b| b as char).collect();
+
+        match final_byte {
+            b'H' => {
+                let mut parts = params.split(';');
+                let y = parts.next().unwrap_or("").parse::<Short>().unwrap_or(1);
+                let x = parts.next().unwrap_or("").parse::<Short>().unwrap_or(1);
+                self.set_position(Coord { x: x - 1, y: y - 1 })?;
+            },
+            b'A' => self.move_by(0, -params.parse::<Short>().unwrap_or(1))?,
+            b'B' => self.move_by(0, params.parse::<Short>().unwrap_or(1))?,
+            b'C' => self.move_by(params.parse::<Short>().unwrap_or(1), 0)?,
+            b'D' => self.move_by(-params.parse::<Short>().unwrap_or(1), 0)?,
+            b'h' if params == "?25" => set_cursor_visible(self.handle(), true)?,
+            b'l' if params == "?25" => set_cursor_visible(self.handle(), false)?,
+            b's' => {
+                let info = screen_buffer_info(self.handle())?;
+                self.saved = Some(info.cursor_position);
+            },
+            b'u' => {
+                if let Some(Coord { x, y }) = self.saved.take() {
+                    self.set_position(Coord { x: x, y: y })?;
+                }
+            },
+            b'q' => self.set_style(&params)?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Approximate a DECSCUSR (`CSI n SP q`) shape change. The Console API
+    /// only exposes a cursor "size" (percentage of the cell it fills), not
+    /// a shape, so block/underline/bar are approximated by size, and
+    /// blinking vs. steady variants aren't distinguishable here.
+    fn set_style(&mut self, params: &str) -> io::Result<()> {
+        let shape = params.trim_end_matches(' ').parse::<u32>().unwrap_or(0);
+        let size = match shape {
+            1 | 2 => 100,
+            3 | 4 => 25,
+            5 | 6 => 10,
+            _ => 100,
+        };
+
+        unsafe {
+            let mut info: ConsoleCursorInfo = mem::zeroed();
+            if GetConsoleCursorInfo(self.handle(), &mut info) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            info.size = size;
+            if SetConsoleCursorInfo(self.handle(), &info) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn move_by(&mut self, dx: Short, dy: Short) -> io::Result<()> {
+        let info = screen_buffer_info(self.handle())?;
+        let position = Coord {
+            x: (info.cursor_position.x + dx).max(0),
+            y: (info.cursor_position.y + dy).max(0),
+        };
+        self.set_position(position)
+    }
+
+    fn set_position(&self, position: Coord) -> io::Result<()> {
+        unsafe {
+            if SetConsoleCursorPosition(self.handle(), position) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + AsRawHandle> Write for WindowsConsole<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            let was_idle = self.parser.is_idle();
+            match self.parser.advance(b) {
+                Some(Event::Unknown(raw)) => {
+                    if !self.apply(&raw)? {
+                        self.inner.write_all(&raw)?;
+                    }
+                },
+                Some(_) => {
+                    // A `CursorPosition`/`DeviceStatus`/`DeviceAttributes`/
+                    // `OscString` report isn't something this backend
+                    // translates, but it's still a real, complete sequence
+                    // that was written out (e.g. literal text a program
+                    // happened to emit that parses as one); forward it
+                    // rather than silently dropping it.
+                    self.inner.write_all(self.parser.raw())?;
+                },
+                None => {
+                    // A byte that isn't the start of, or inside, a
+                    // recognized escape sequence is ordinary data (text,
+                    // line breaks, ...) and must reach the console
+                    // unchanged.
+                    if was_idle && self.parser.is_idle() {
+                        self.inner.write_all(&[b])?;
+                    }
+                },
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + AsRawHandle> DetectCursorPos for WindowsConsole<W> {
+    fn cursor_pos(&mut self) -> io::Result<(u16, u16)> {
+        let info = screen_buffer_info(self.handle())?;
+        Ok(((info.cursor_position.x + 1) as u16, (info.cursor_position.y + 1) as u16))
+    }
+}
@@ -0,0 +1,7 @@
+//! Termion is a pure Rust, bindless library for low-level handling, manipulating
+//! and reading information about terminals.
+
+pub mod cursor;
+pub mod size;
+
+pub use size::terminal_size;
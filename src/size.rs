@@ -0,0 +1,45 @@
+//! Terminal size detection.
+
+use std::io::{self, Write};
+
+use cursor::{self, DetectCursorPos};
+use sys::size;
+use sys::tty;
+
+/// Get the size of the terminal, as (columns, rows).
+///
+/// This first asks the kernel via the `TIOCGWINSZ` ioctl. Some situations
+/// (a pipe, certain remote sessions) leave that unavailable, or make it
+/// report zero in either dimension; when that happens, this falls back to
+/// the trick line editors use to work around it: save the cursor, move it
+/// far past any plausible screen edge, read back where the terminal
+/// clamped it to, then restore the cursor.
+pub fn terminal_size() -> io::Result<(u16, u16)> {
+    match size::terminal_size() {
+        Ok((0, _)) | Ok((_, 0)) | Err(_) => terminal_size_by_cursor_report(),
+        ok => ok,
+    }
+}
+
+fn via_cursor_report<W: Write + DetectCursorPos>(mut tty: W) -> io::Result<(u16, u16)> {
+    write!(tty, "{}", cursor::Save)?;
+    write!(tty, "{}", cursor::Goto(9999, 9999))?;
+    tty.flush()?;
+
+    let size = tty.cursor_pos();
+
+    write!(tty, "{}", cursor::Restore)?;
+    tty.flush()?;
+
+    size
+}
+
+#[cfg(not(windows))]
+fn terminal_size_by_cursor_report() -> io::Result<(u16, u16)> {
+    via_cursor_report(tty::get_tty()?)
+}
+
+#[cfg(windows)]
+fn terminal_size_by_cursor_report() -> io::Result<(u16, u16)> {
+    via_cursor_report(cursor::windows::WindowsConsole::new(tty::get_tty()?))
+}